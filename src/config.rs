@@ -1,3 +1,6 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
 /// Configuration for the readline prompt
 pub struct RlwrapConfig {
     /// The prefix of the prompt. E.g. "cool app> ".
@@ -6,6 +9,22 @@ pub struct RlwrapConfig {
     /// If this is enabled it will also stop the prompt.
     /// You may set this to false if you want to handle interrupt signals.
     pub stop_on_ctrl_c: bool,
+    /// Optional file to load history from on startup and append submitted lines to.
+    /// When `None`, history is kept in memory only.
+    pub history_file: Option<PathBuf>,
+    /// Maximum number of entries kept in history. The oldest entries are dropped
+    /// once this is exceeded.
+    pub history_size: usize,
+    /// How long `Rlwrap::wait_child` polls for the spawned child to exit before
+    /// giving up and returning `None`. `None` means wait indefinitely.
+    pub wait_timeout: Option<Duration>,
+    /// Static words completed, by longest-common-prefix matching, when Tab is
+    /// pressed. Combined with `completer`'s candidates, if set.
+    pub completions: Vec<String>,
+    /// Optional callback asked for completion candidates for the word under
+    /// the cursor, e.g. to supply context-sensitive completions. Combined
+    /// with `completions`'s candidates, if any.
+    pub completer: Option<Box<dyn Fn(&str) -> Vec<String> + Send>>,
 }
 
 impl Default for RlwrapConfig {
@@ -13,6 +32,11 @@ impl Default for RlwrapConfig {
         Self {
             prefix: "> ".to_owned(),
             stop_on_ctrl_c: true,
+            history_file: None,
+            history_size: 1000,
+            wait_timeout: None,
+            completions: Vec::new(),
+            completer: None,
         }
     }
 }
@@ -1,52 +1,81 @@
 use std::{
-    ffi::CStr,
-    fs::File,
-    io::{self, stdout, BufRead, BufReader, Read, Stdout, Write},
-    mem::ManuallyDrop,
-    os::unix::prelude::FromRawFd,
-    process,
-    sync::{Arc, Mutex, Weak},
-    thread::{self, JoinHandle},
+    fs::OpenOptions,
+    io::{self, Read, Write},
+    path::Path,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
 use config::RlwrapConfig;
-use libc::{STDERR_FILENO as STDERR, STDIN_FILENO as STDIN, STDOUT_FILENO as STDOUT};
-use termion::{
-    event::{Event, Key},
-    raw::{IntoRawMode, RawTerminal},
-};
+use sys::PtyBackend;
+use termion::event::{Event, Key};
 
 pub mod config;
+mod sys;
+
+#[cfg(target_family = "unix")]
+pub use sys::unix::RAW_TERMINAL_STATE;
+
+/// A cheap, cloneable way to ask a running [`Rlwrap::run`] loop to stop from
+/// another thread, without wrapping the whole struct in a `Mutex`.
+#[derive(Clone)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+/// Outcome of a single non-blocking read from the pty master.
+enum MasterRead {
+    /// The pty master is at EOF; no more output will ever arrive.
+    Eof,
+    /// No output is available right now, but the pty master isn't at EOF.
+    WouldBlock,
+    /// Some output was read (and any complete lines in it printed).
+    Data,
+}
 
-#[cfg(target_family = "windows")]
-compile_error!("Not implemented on windows");
-
-/// Previous terminal state.
-/// This is static so the application can try revert it when a panic ocurs.
-pub static RAW_TERMINAL_STATE: Mutex<Option<RawTerminal<Stdout>>> = Mutex::new(None);
+impl StopHandle {
+    /// Requests that the event loop stop at its next iteration.
+    pub fn request_stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
 
-/// Readline prompt struct.
-/// This struct will be shared across two threads,
-/// one that will read stdin and one that will write to stdout.
+/// Readline prompt struct, driven by a single-threaded, `poll`-based event loop
+/// (see [`Rlwrap::run`]) instead of the blocking reader/writer threads this used
+/// to spawn.
 pub struct Rlwrap {
     is_running: bool,
-
-    /// Original stdin file descriptor.
-    original_stdin: i32,
-    /// Original stdout file descriptor.
-    original_stdout: i32,
-    /// Original stderr file descriptor.
-    original_stderr: i32,
-
-    /// Terminal created.
-    pty: i32,
+    stop_requested: Arc<AtomicBool>,
+
+    /// The real stdin descriptor, polled by `run` and read by `pump_stdin`.
+    stdin_descriptor: sys::RawDescriptor,
+
+    /// The pseudo-terminal master end(s) created by `setup`.
+    master: sys::MasterFd,
+    /// The pty slave, held until `spawn` attaches a child to it (or `stop`
+    /// closes it if no child was ever spawned).
+    slave: Option<sys::SlaveHandle>,
+    /// The child process `spawn` attached to the pty, if any. `run` watches
+    /// this and tears the prompt down once it exits.
+    child: Option<sys::SpawnedChild>,
+
+    /// Reads raw keystrokes from the real stdin.
+    stdin_reader: Box<dyn Read + Send>,
+    /// Trailing bytes from the last `pump_stdin` read that didn't form a
+    /// complete UTF-8 sequence yet, held over until the rest arrives.
+    pending_stdin: Vec<u8>,
+    /// Reads the wrapped program's output from the pty master.
+    pty_reader: Box<dyn Read + Send>,
+    /// Writes submitted lines to the wrapped program's stdin, via the pty master.
+    pty_writer: Box<dyn Write + Send>,
+    /// Bytes read from `pty_reader` that don't yet make up a full line.
+    output_buffer: Vec<u8>,
 
     /// Original output.
-    /// This is a file struct used to write data to the original terminal
-    /// and wrapped in ManuallyDrop to avoid closing the original fd.
-    original_output_file: Option<ManuallyDrop<File>>,
-
-    pub out_thread: Option<JoinHandle<()>>,
+    /// A thin, non-owning writer over the real stdout, used to draw the prompt.
+    original_output: Option<Box<dyn Write + Send>>,
 
     /// Configuration for rlwrap.
     pub config: RlwrapConfig,
@@ -54,71 +83,437 @@ pub struct Rlwrap {
     /// The current buffer being edited.
     pub buffer: String,
 
-    /// Cursor position in the buffer
+    /// Cursor position as a *char* index into `buffer` (not a byte offset, and
+    /// not a display column — see `cursor_byte_offset`/`redraw`).
     pub cursor: u16,
 
     /// Terminal size (rows, cols).
     pub terminal_size: (u16, u16),
+
+    /// Previously submitted lines, oldest first.
+    pub history: Vec<String>,
+
+    /// Index into `history` while navigating with Up/Down.
+    /// `None` means the buffer is not currently showing a history entry.
+    history_index: Option<usize>,
+
+    /// Whether a Ctrl-R reverse incremental search is in progress.
+    search_active: bool,
+    /// Query typed so far during a reverse incremental search.
+    search_query: String,
+    /// Index of the history entry currently matched by `search_query`.
+    search_match_index: Option<usize>,
+    /// Buffer/cursor saved when entering search, restored if the search is aborted.
+    pre_search_buffer: String,
+    pre_search_cursor: u16,
+
+    /// The word Tab last completed against, if the very next key is also Tab
+    /// and nothing else has changed the buffer — used to tell a "narrow the
+    /// common prefix" Tab from a "list every candidate" second Tab.
+    last_tab_word: Option<String>,
 }
 
 impl Rlwrap {
-    /// Sets up the pseudo terminal and make the dup/dup2 syscalls.
-    pub fn setup(config: RlwrapConfig) -> Result<Arc<Mutex<Self>>, io::Error> {
-        // Turn raw mode
-        let raw_term = stdout().into_raw_mode()?;
+    /// Sets up the pseudo terminal. Does not spawn a child or start reading
+    /// input; call `spawn` to attach a wrapped program and `run` to drive the
+    /// event loop.
+    pub fn setup(config: RlwrapConfig) -> Result<Self, io::Error> {
+        let backend = sys::backend();
+        backend.enable_raw_mode()?;
+
+        let (master, slave) = backend.open()?;
+        let stdin_descriptor = sys::stdin_descriptor();
+
+        sys::set_nonblocking(stdin_descriptor)?;
+        sys::set_master_nonblocking(&master)?;
+
+        let history = config
+            .history_file
+            .as_deref()
+            .map(|path| load_history(path, config.history_size))
+            .transpose()?
+            .unwrap_or_default();
+
+        let mut rlwrap = Self {
+            is_running: true,
+            stop_requested: Arc::new(AtomicBool::new(false)),
+            original_output: Some(sys::descriptor_writer(sys::stdout_descriptor())),
+            stdin_reader: sys::descriptor_reader(stdin_descriptor),
+            pending_stdin: Vec::new(),
+            pty_reader: sys::master_reader(&master),
+            pty_writer: sys::master_writer(&master),
+            output_buffer: Vec::new(),
+            master,
+            slave: Some(slave),
+            child: None,
+            stdin_descriptor,
+            config,
+            buffer: String::new(),
+            cursor: 0,
+            terminal_size: termion::terminal_size()?,
+            history,
+            history_index: None,
+            search_active: false,
+            search_query: String::new(),
+            search_match_index: None,
+            pre_search_buffer: String::new(),
+            pre_search_cursor: 0,
+            last_tab_word: None,
+        };
+        rlwrap.redraw();
 
-        if let Ok(mut guard) = RAW_TERMINAL_STATE.lock() {
-            *guard = Some(raw_term);
-        } else {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Failed to aquire RAW_TERMINAL_STATE lock",
-            ));
+        Ok(rlwrap)
+    }
+
+    /// Spawns `cmd` attached to the pty (instead of the process-wide
+    /// stdin/stdout/stderr redirection `setup` used to rely on), so `run` can
+    /// detect when it exits or (via `wait_child`) hangs.
+    pub fn spawn(&mut self, cmd: Command) -> io::Result<()> {
+        let slave = self
+            .slave
+            .take()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "pty slave already consumed"))?;
+        let child = sys::spawn_attached(&self.master, slave, cmd)?;
+        self.child = Some(child);
+        Ok(())
+    }
+
+    /// Waits for the spawned child to exit, polling rather than blocking so
+    /// callers can enforce a kill-after-timeout policy on stuck subprocesses
+    /// via `kill_child`. Polls for up to `config.wait_timeout` (indefinitely
+    /// if `None`). Returns `Ok(None)` if it's still running once the timeout
+    /// elapses, or if no child was spawned.
+    pub fn wait_child(&mut self) -> io::Result<Option<i32>> {
+        let deadline = self.config.wait_timeout.map(|timeout| Instant::now() + timeout);
+        loop {
+            match &mut self.child {
+                Some(child) => {
+                    if let Some(code) = sys::try_wait(child)? {
+                        return Ok(Some(code));
+                    }
+                }
+                None => return Ok(None),
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Ok(None);
+            }
+            std::thread::sleep(Duration::from_millis(20));
         }
+    }
 
-        let original_stdin = dup(STDIN)?;
-        let original_stdout = dup(STDOUT)?;
-        let original_stderr = dup(STDERR)?;
+    /// Forcibly terminates the spawned child, e.g. after `wait_child` times out.
+    pub fn kill_child(&mut self) -> io::Result<()> {
+        match &mut self.child {
+            Some(child) => sys::kill_child(child),
+            None => Ok(()),
+        }
+    }
 
-        let original_output_file = ManuallyDrop::new(unsafe { File::from_raw_fd(original_stdout) });
+    /// Returns a handle that can be used to request this loop stop from another
+    /// thread.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle(self.stop_requested.clone())
+    }
 
-        let pty = open_pty(libc::O_RDWR)?;
-        grantpt(pty)?;
-        unlockpt(pty)?;
-        let pty_name = pty_name(pty)
-            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to get pty name"))?;
-        let pty_child = open_file(&pty_name, libc::O_RDWR)?;
+    /// Runs the event loop: polls stdin and the pty master, handling whichever
+    /// is ready, until `stop`/`StopHandle::request_stop` is called, the spawned
+    /// child exits, or either side reaches EOF. Returns when the loop stops.
+    pub fn run(&mut self) -> io::Result<()> {
+        while self.is_running && !self.stop_requested.load(Ordering::SeqCst) {
+            let child_exited = match &mut self.child {
+                Some(child) => sys::try_wait(child)?.is_some(),
+                None => false,
+            };
+            let ready = sys::wait_ready(self.stdin_descriptor, &self.master)?;
+            if ready.master && !self.pump_master_output()? {
+                break;
+            }
+            if ready.stdin && !self.pump_stdin()? {
+                break;
+            }
+            if child_exited {
+                // Drain whatever output the child wrote before exiting: it may
+                // have flushed its last bytes in the same scheduling gap as
+                // the read above, so a single `pump_master_output` here isn't
+                // guaranteed to have seen all of it yet.
+                loop {
+                    match self.pump_master_output_once()? {
+                        MasterRead::Eof | MasterRead::WouldBlock => break,
+                        MasterRead::Data => {}
+                    }
+                }
+                break;
+            }
+        }
+        if self.is_running {
+            self.stop()?;
+        }
+        Ok(())
+    }
 
-        let rlwrap = Arc::new(Mutex::new(Self {
-            is_running: true,
-            pty,
-            original_output_file: Some(original_output_file),
-            original_stdin,
-            original_stdout,
-            original_stderr,
-            config,
-            out_thread: None,
-            buffer: String::new(),
-            cursor: 0,
-            terminal_size: termion::terminal_size()?,
-        }));
+    /// Reads whatever output is currently available from the pty master and
+    /// prints any complete lines. Returns `Ok(false)` on EOF.
+    fn pump_master_output(&mut self) -> io::Result<bool> {
+        Ok(!matches!(self.pump_master_output_once()?, MasterRead::Eof))
+    }
 
-        let out_thread = output_pipe_thread(Arc::downgrade(&rlwrap), pty);
-        rlwrap.lock().unwrap().out_thread = Some(out_thread);
-        readline_thread(Arc::downgrade(&rlwrap), original_stdin, pty);
+    /// Does a single, non-blocking read of the pty master and prints any
+    /// complete lines it yields. Split out from `pump_master_output` so
+    /// callers that need to tell "no data right now" apart from "no data
+    /// ever again" (e.g. draining after the child exits) can do so.
+    fn pump_master_output_once(&mut self) -> io::Result<MasterRead> {
+        let mut buf = [0u8; 4096];
+        match self.pty_reader.read(&mut buf) {
+            Ok(0) => Ok(MasterRead::Eof),
+            Ok(n) => {
+                self.output_buffer.extend_from_slice(&buf[..n]);
+                while let Some(pos) = self.output_buffer.iter().position(|&b| b == b'\n') {
+                    let line: Vec<u8> = self.output_buffer.drain(..=pos).collect();
+                    let line = String::from_utf8_lossy(&line);
+                    let line = line.trim_end_matches(['\r', '\n']).to_owned();
+                    self.print(&line);
+                }
+                Ok(MasterRead::Data)
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(MasterRead::WouldBlock),
+            Err(e) => Err(e),
+        }
+    }
 
-        dup2(pty_child, STDIN)?;
-        dup2(pty_child, STDOUT)?;
-        dup2(pty_child, STDERR)?;
+    /// Reads whatever keystrokes are currently available from stdin and feeds
+    /// them through the line editor. Returns `Ok(false)` on EOF.
+    fn pump_stdin(&mut self) -> io::Result<bool> {
+        let mut buf = [0u8; 1024];
+        let n = match self.stdin_reader.read(&mut buf) {
+            Ok(0) => return Ok(false),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+            Err(e) => return Err(e),
+        };
+
+        // Carry over any trailing bytes left buffered from the previous read
+        // that didn't form a complete UTF-8 sequence, and hold back the same
+        // from this read, so a multibyte char split across two `read`s isn't
+        // fed to the parser half-decoded.
+        let mut data = std::mem::take(&mut self.pending_stdin);
+        data.extend_from_slice(&buf[..n]);
+        let complete_len = data.len() - incomplete_utf8_suffix_len(&data);
+        self.pending_stdin = data[complete_len..].to_vec();
+
+        // All the complete bytes are already in `data`, so multi-byte escape
+        // sequences are parsed straight out of memory instead of blocking the
+        // loop on another read.
+        let mut bytes = data[..complete_len].iter().map(|b| Ok(*b));
+        while let Some(Ok(byte)) = bytes.next() {
+            if let Ok(Event::Key(k)) = termion::event::parse_event(byte, &mut bytes) {
+                self.handle_key(k)?;
+            }
+        }
+        Ok(true)
+    }
 
-        close_file(pty_child)?;
+    /// Byte offset of `self.cursor` (a char index) into `buffer`.
+    fn cursor_byte_offset(&self) -> usize {
+        self.buffer
+            .char_indices()
+            .nth(self.cursor as usize)
+            .map(|(byte, _)| byte)
+            .unwrap_or(self.buffer.len())
+    }
 
-        rlwrap.lock().unwrap().redraw();
+    /// Number of chars in `buffer`, i.e. the valid range for `self.cursor`.
+    fn char_count(&self) -> u16 {
+        self.buffer.chars().count() as u16
+    }
 
-        Ok(rlwrap)
+    /// The word immediately before the cursor, and its starting char index,
+    /// used as the prefix Tab completion is matched against.
+    fn current_word(&self) -> (u16, String) {
+        let byte_pos = self.cursor_byte_offset();
+        let start_byte = self.buffer[..byte_pos]
+            .rfind(char::is_whitespace)
+            .map_or(0, |pos| pos + 1);
+        let start_char = self.buffer[..start_byte].chars().count() as u16;
+        (start_char, self.buffer[start_byte..byte_pos].to_owned())
     }
+
+    /// Replaces the word starting at char index `start` and ending at the
+    /// cursor with `replacement`, moving the cursor to the end of it.
+    fn replace_word(&mut self, start: u16, replacement: &str) {
+        let start_byte = self
+            .buffer
+            .char_indices()
+            .nth(start as usize)
+            .map(|(byte, _)| byte)
+            .unwrap_or(self.buffer.len());
+        let end_byte = self.cursor_byte_offset();
+        self.buffer.replace_range(start_byte..end_byte, replacement);
+        self.cursor = start + replacement.chars().count() as u16;
+    }
+
+    /// Completion candidates for `word`, combining `config.completions` (matched
+    /// by prefix) with whatever `config.completer` returns, deduplicated and sorted.
+    fn completions_for(&self, word: &str) -> Vec<String> {
+        let mut candidates: Vec<String> = self
+            .config
+            .completions
+            .iter()
+            .filter(|c| c.starts_with(word))
+            .cloned()
+            .collect();
+        if let Some(completer) = &self.config.completer {
+            candidates.extend(completer(word));
+        }
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
+    /// Applies a single parsed key event to the line editor state.
+    fn handle_key(&mut self, k: Key) -> io::Result<()> {
+        if self.search_active {
+            match k {
+                Key::Char('\n') | Key::Left | Key::Right => {
+                    if let Some(idx) = self.search_match_index {
+                        self.buffer = self.history[idx].clone();
+                        self.cursor = self.char_count();
+                    }
+                    self.search_active = false;
+                }
+                Key::Char(c) => {
+                    self.search_query.push(c);
+                    self.search_match_index =
+                        find_history_match(&self.history, &self.search_query, None);
+                }
+                Key::Ctrl('r') => {
+                    let before = self.search_match_index;
+                    self.search_match_index =
+                        find_history_match(&self.history, &self.search_query, before);
+                }
+                Key::Ctrl('g') | Key::Ctrl('c') => {
+                    self.buffer = self.pre_search_buffer.clone();
+                    self.cursor = self.pre_search_cursor;
+                    self.search_active = false;
+                }
+                Key::Backspace => {
+                    self.search_query.pop();
+                    self.search_match_index =
+                        find_history_match(&self.history, &self.search_query, None);
+                }
+                _ => {}
+            }
+            self.redraw();
+            return Ok(());
+        }
+        if !matches!(k, Key::Char('\t')) {
+            self.last_tab_word = None;
+        }
+        match k {
+            Key::Char('\t') => {
+                let (start, word) = self.current_word();
+                let candidates = self.completions_for(&word);
+                match tab_action(&word, &candidates, self.last_tab_word.as_deref()) {
+                    TabAction::None => {}
+                    TabAction::Complete(replacement) => {
+                        self.replace_word(start, &replacement);
+                        self.last_tab_word = Some(replacement);
+                    }
+                    TabAction::List => {
+                        for candidate in &candidates {
+                            self.print(candidate);
+                        }
+                    }
+                    TabAction::Remember(word) => {
+                        self.last_tab_word = Some(word);
+                    }
+                }
+            }
+            Key::Char(c) => {
+                let byte_pos = self.cursor_byte_offset();
+                self.buffer.insert(byte_pos, c);
+                self.cursor += 1;
+                if c == '\n' {
+                    self.pty_writer.write_all(self.buffer.as_bytes())?;
+                    self.record_history(&self.buffer.clone());
+                    self.buffer.clear();
+                    self.cursor = 0;
+                    self.history_index = None;
+                }
+            }
+            Key::Ctrl(c) => {
+                if c == 'd' {
+                    self.buffer.push(4u8 as char);
+                    self.pty_writer.write_all(self.buffer.as_bytes())?;
+                    self.buffer.clear();
+                    self.cursor = 0;
+                    self.history_index = None;
+                }
+                if c == 'c' {
+                    if self.config.stop_on_ctrl_c {
+                        self.stop_requested.store(true, Ordering::SeqCst);
+                    }
+                    if let Err(e) = sys::send_interrupt() {
+                        eprintln!("Failed to send interrupt signal: {e}");
+                    }
+                }
+                if c == 'r' {
+                    self.pre_search_buffer = self.buffer.clone();
+                    self.pre_search_cursor = self.cursor;
+                    self.search_query.clear();
+                    self.search_match_index = None;
+                    self.search_active = true;
+                }
+            }
+            Key::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    let byte_pos = self.cursor_byte_offset();
+                    let ch_len = self.buffer[byte_pos..].chars().next().map_or(0, char::len_utf8);
+                    self.buffer.drain(byte_pos..byte_pos + ch_len);
+                }
+            }
+            Key::Left => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                }
+            }
+            Key::Right => {
+                if self.cursor < self.char_count() {
+                    self.cursor += 1;
+                }
+            }
+            Key::Up => {
+                if !self.history.is_empty() {
+                    let new_index = match self.history_index {
+                        Some(i) if i > 0 => i - 1,
+                        Some(i) => i,
+                        None => self.history.len() - 1,
+                    };
+                    self.history_index = Some(new_index);
+                    self.buffer = self.history[new_index].clone();
+                    self.cursor = self.char_count();
+                }
+            }
+            Key::Down => {
+                if let Some(i) = self.history_index {
+                    if i + 1 < self.history.len() {
+                        self.history_index = Some(i + 1);
+                        self.buffer = self.history[i + 1].clone();
+                    } else {
+                        self.history_index = None;
+                        self.buffer.clear();
+                    }
+                    self.cursor = self.char_count();
+                }
+            }
+            _ => {}
+        }
+        self.redraw();
+        Ok(())
+    }
+
     pub fn print(&mut self, s: &str) {
-        if let Some(out) = &mut self.original_output_file {
+        if let Some(out) = &mut self.original_output {
             write!(out, "{}\r{s}\r\n", termion::clear::CurrentLine).ok();
             self.redraw();
         } else {
@@ -128,8 +523,34 @@ impl Rlwrap {
         }
     }
     pub fn redraw(&mut self) {
-        if let Some(out) = &mut self.original_output_file {
-            let cursor_x = (self.config.prefix.len() as u16) + self.cursor + 1;
+        if self.original_output.is_none() {
+            return;
+        }
+        if self.search_active {
+            let matched = self
+                .search_match_index
+                .and_then(|i| self.history.get(i))
+                .map(String::as_str)
+                .unwrap_or("");
+            let prompt = format!("(reverse-i-search)`{}': ", self.search_query);
+            let cursor_x = display_width(&prompt) + display_width(matched) + 1;
+            let out = self.original_output.as_mut().unwrap();
+            write!(
+                out,
+                "{}{}\r{}{}{}",
+                termion::cursor::Goto(0, self.terminal_size.1),
+                termion::clear::CurrentLine,
+                prompt,
+                matched,
+                termion::cursor::Goto(cursor_x, self.terminal_size.1),
+            )
+            .ok();
+        } else {
+            let byte_pos = self.cursor_byte_offset();
+            let cursor_x = display_width(&self.config.prefix)
+                + display_width(&self.buffer[..byte_pos])
+                + 1;
+            let out = self.original_output.as_mut().unwrap();
             write!(
                 out,
                 "{}{}\r{}{}{}",
@@ -142,22 +563,37 @@ impl Rlwrap {
             .ok();
         }
     }
-    /// Closes all the pipes created by rlwrap and restores stdin, stdout and stderr.
-    /// Some messages may be still being processed by the output thread.
-    /// If you want to wait for all messages to be printed, use Rlwrap::stop_gracefully.
+    /// Records a submitted line in history and trims both the in-memory list
+    /// and the history file (if configured) down to `history_size` entries.
+    fn record_history(&mut self, line: &str) {
+        let line = line.trim_end_matches('\n');
+        if line.is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        self.history.push(line.to_owned());
+        let size = self.config.history_size;
+        if self.history.len() > size {
+            let overflow = self.history.len() - size;
+            self.history.drain(0..overflow);
+        }
+        if let Some(path) = &self.config.history_file {
+            if let Ok(mut file) = OpenOptions::new().create(true).write(true).truncate(true).open(path) {
+                for entry in &self.history {
+                    writeln!(file, "{entry}").ok();
+                }
+            }
+        }
+    }
+    /// Closes the pty and restores the terminal to its original mode.
     pub fn stop(&mut self) -> Result<(), io::Error> {
         if self.is_running {
-            self.original_output_file.take();
-            dup2(self.original_stdin, STDIN)?;
-            dup2(self.original_stdout, STDOUT)?;
-            dup2(self.original_stderr, STDERR)?;
-            close_file(self.pty)?;
-            close_file(self.original_stdin)?;
-            close_file(self.original_stdout)?;
-            close_file(self.original_stderr)?;
-            if let Ok(mut guard) = RAW_TERMINAL_STATE.lock() {
-                guard.take();
+            self.original_output.take();
+            let backend = sys::backend();
+            if let Some(slave) = self.slave.take() {
+                backend.close_slave(slave)?;
             }
+            backend.close_master(&self.master)?;
+            backend.disable_raw_mode()?;
             self.is_running = false;
             println!();
             Ok(())
@@ -165,22 +601,6 @@ impl Rlwrap {
             Err(io::Error::new(io::ErrorKind::Other, "Not running"))
         }
     }
-
-    /// Tries to gracefully stop the rlwrap prompt by waiting for the output thread.
-    /// This function takes a Mutex instead of Self to be able to unlock it and make the
-    /// thread lock it again.
-    /// TODO: I should find a better way to do this :(
-    pub fn stop_gracefully(rlwrap: &Mutex<Self>) -> Result<(), io::Error> {
-        let mut lock = rlwrap.lock().unwrap();
-        let out_thread = lock.out_thread.take();
-        lock.stop()?;
-        drop(lock);
-        if let Some(t) = out_thread {
-            t.join()
-                .map_err(|_| io::Error::new(io::ErrorKind::Other, "Output thread failed"))?;
-        }
-        Ok(())
-    }
 }
 
 impl Drop for Rlwrap {
@@ -189,186 +609,254 @@ impl Drop for Rlwrap {
     }
 }
 
-fn readline_thread(rlwrap: Weak<Mutex<Rlwrap>>, from: i32, to: i32) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let mut from = ManuallyDrop::new(unsafe { File::from_raw_fd(from) });
-        let from_ref: &mut File = &mut from;
-        let mut to = ManuallyDrop::new(unsafe { File::from_raw_fd(to) });
-        let mut bytes = from_ref.bytes();
-        while let Some(byte) = bytes.next() {
-            if let Some(rlwrap) = rlwrap.upgrade() {
-                if let Ok(byte) = byte {
-                    let event = termion::event::parse_event(byte, &mut bytes);
-                    if let Ok(event) = event {
-                        if let Event::Key(k) = event {
-                            let mut guard = rlwrap.lock().unwrap();
-                            match k {
-                                Key::Char(c) => {
-                                    let cpos = guard.cursor as usize;
-                                    guard.buffer.insert(cpos, c);
-                                    guard.cursor += 1;
-                                    if c == '\n' {
-                                        if to.write_all(guard.buffer.as_bytes()).is_err() {
-                                            break;
-                                        }
-                                        guard.buffer.clear();
-                                        guard.cursor = 0;
-                                    }
-                                }
-                                Key::Ctrl(c) => {
-                                    if c == 'd' {
-                                        guard.buffer.push(4u8 as char);
-                                        if to.write_all(guard.buffer.as_bytes()).is_err() {
-                                            break;
-                                        }
-                                        guard.buffer.clear();
-                                        guard.cursor = 0;
-                                    }
-                                    if c == 'c' {
-                                        if guard.config.stop_on_ctrl_c {
-                                            guard.stop().unwrap();
-                                        }
-                                        if let Err(e) = kill(process::id() as i32, libc::SIGINT) {
-                                            eprintln!("Failed to send interrupt signal: {e}");
-                                        }
-                                    }
-                                }
-                                Key::Backspace => {
-                                    let cur = guard.cursor as usize;
-                                    let blen = guard.buffer.len();
-                                    if blen > 0 && cur <= blen {
-                                        guard.buffer.remove(cur as usize - 1);
-                                        guard.cursor -= 1;
-                                    }
-                                },
-                                Key::Left => {
-                                    if guard.cursor > 0 {
-                                        guard.cursor -= 1;
-                                    }
-                                },
-                                Key::Right => {
-                                    if (guard.cursor as usize) < guard.buffer.len() {
-                                        guard.cursor += 1;
-                                    }
-                                }
-                                _ => {}
-                            }
-                            guard.redraw();
-                        }
-                    } else {
-                        break;
-                    }
-                } else {
-                    break;
-                }
-            } else {
-                break;
-            }
-        }
-    })
+/// Loads history entries (one per line) from `path`, keeping only the most
+/// recent `size` of them. Returns an empty `Vec` if the file does not exist yet.
+fn load_history(path: &Path, size: usize) -> Result<Vec<String>, io::Error> {
+    let mut lines: Vec<String> = match std::fs::File::open(path) {
+        Ok(file) => io::BufRead::lines(io::BufReader::new(file)).collect::<Result<_, _>>()?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    if lines.len() > size {
+        let overflow = lines.len() - size;
+        lines.drain(0..overflow);
+    }
+    Ok(lines)
 }
 
-fn output_pipe_thread(rlwrap: Weak<Mutex<Rlwrap>>, from: i32) -> JoinHandle<()> {
-    thread::spawn(move || {
-        let mut from = ManuallyDrop::new(unsafe { File::from_raw_fd(from) });
-        let file: &mut File = &mut from;
-        for line in BufReader::new(file).lines() {
-            if let Some(rlwrap) = rlwrap.upgrade() {
-                if let Ok(line) = line {
-                    let mut guard = rlwrap.lock().unwrap();
-                    guard.print(&line);
-                } else {
-                    break;
-                }
+/// Finds the most recent entry in `history` containing `query` as a substring.
+/// When `before` is `Some(idx)`, only entries strictly before `idx` are considered,
+/// which lets repeated Ctrl-R presses jump to the next older match.
+fn find_history_match(history: &[String], query: &str, before: Option<usize>) -> Option<usize> {
+    if query.is_empty() {
+        return None;
+    }
+    let upper = before.unwrap_or(history.len());
+    history[..upper].iter().rposition(|line| line.contains(query))
+}
+
+/// The longest string that is a prefix of every entry in `candidates`, or the
+/// empty string if `candidates` is empty.
+fn longest_common_prefix(candidates: &[String]) -> String {
+    let mut prefix = match candidates.first() {
+        Some(first) => first.clone(),
+        None => return String::new(),
+    };
+    for candidate in &candidates[1..] {
+        let common = prefix
+            .chars()
+            .zip(candidate.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        prefix = prefix.chars().take(common).collect();
+    }
+    prefix
+}
+
+/// What a Tab press should do, decided by [`tab_action`].
+#[derive(Debug, PartialEq, Eq)]
+enum TabAction {
+    /// No candidates matched the word under the cursor; do nothing.
+    None,
+    /// Replace the word with this: either the single matching candidate, or
+    /// a longest common prefix wider than what was typed.
+    Complete(String),
+    /// List every candidate: a repeat Tab on a word with no wider common
+    /// prefix left to offer.
+    List,
+    /// Remember `word` as the one this Tab press completed against, so an
+    /// immediately-following Tab on the same word lists candidates instead
+    /// of widening it further.
+    Remember(String),
+}
+
+/// Decides what a Tab press on `word` should do, given its completion
+/// `candidates` and the word the *previous* Tab press completed against (if
+/// the last key pressed was also Tab). Pulled out of `handle_key` as a pure
+/// function so the widen/list state machine is unit-testable.
+fn tab_action(word: &str, candidates: &[String], last_tab_word: Option<&str>) -> TabAction {
+    match candidates {
+        [] => TabAction::None,
+        [only] => TabAction::Complete(only.clone()),
+        _ => {
+            let prefix = longest_common_prefix(candidates);
+            if prefix.chars().count() > word.chars().count() {
+                TabAction::Complete(prefix)
+            } else if last_tab_word == Some(word) {
+                TabAction::List
             } else {
-                break;
+                TabAction::Remember(word.to_owned())
             }
         }
-    })
+    }
 }
 
-/// Wrapper around libc::dup
-fn dup(fd: i32) -> Result<i32, io::Error> {
-    let result = unsafe { libc::dup(fd) };
-    if result == -1 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(result)
+/// How many terminal columns `c` occupies: 0 for zero-width combining marks,
+/// 2 for wide CJK/fullwidth/emoji-ish ranges, 1 otherwise. A simplified subset
+/// of Unicode East Asian Width, since pulling in a dedicated crate isn't worth
+/// it for the ranges the prompt actually needs to get right.
+fn char_width(c: char) -> u16 {
+    match c as u32 {
+        0x0300..=0x036F | 0x200B..=0x200F | 0xFE00..=0xFE0F => 0,
+        0x1100..=0x115F
+        | 0x2E80..=0xA4CF
+        | 0xAC00..=0xD7A3
+        | 0xF900..=0xFAFF
+        | 0xFF00..=0xFF60
+        | 0xFFE0..=0xFFE6
+        | 0x1F300..=0x1FAFF
+        | 0x20000..=0x3FFFD => 2,
+        _ => 1,
     }
 }
 
-/// Wrapper around libc::dup2
-fn dup2(src: i32, dest: i32) -> Result<i32, io::Error> {
-    let result = unsafe { libc::dup2(src, dest) };
-    if result == -1 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(result)
-    }
+/// Display width of `s` in terminal columns, summing `char_width` over its chars.
+fn display_width(s: &str) -> u16 {
+    s.chars().map(char_width).sum()
 }
 
-/// Wrapper around libc::kill
-fn kill(pid: i32, sig: i32) -> Result<(), io::Error> {
-    if unsafe { libc::kill(pid, sig) } == 0 {
-        Ok(())
-    } else {
-        Err(io::Error::last_os_error())
+/// Length of the trailing run of bytes in `data` that form an incomplete UTF-8
+/// sequence (i.e. a lead byte whose continuation bytes haven't all arrived
+/// yet), or `0` if `data` ends on a complete char.
+fn incomplete_utf8_suffix_len(data: &[u8]) -> usize {
+    for back in 1..=data.len().min(4) {
+        let idx = data.len() - back;
+        let byte = data[idx];
+        if byte & 0b1100_0000 == 0b1000_0000 {
+            continue; // continuation byte: keep scanning back for its lead byte
+        }
+        let seq_len = match byte {
+            0x00..=0x7F => 1,
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => 1, // not a valid lead byte; treat as complete so it isn't buffered forever
+        };
+        return if idx + seq_len > data.len() {
+            data.len() - idx
+        } else {
+            0
+        };
     }
+    0
 }
 
-/// Wrapper around libc::posix_openpt
-fn open_pty(flags: i32) -> Result<i32, io::Error> {
-    let result = unsafe { libc::posix_openpt(flags) };
-    if result == -1 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(result)
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_history_match_prefers_most_recent() {
+        let history = vec!["cd /tmp".to_owned(), "ls".to_owned(), "cd /home".to_owned()];
+        assert_eq!(find_history_match(&history, "cd", None), Some(2));
     }
-}
 
-/// Wrapper around libc::ptsname
-fn pty_name(fd: i32) -> Option<String> {
-    let result = unsafe { libc::ptsname(fd) };
-    if result.is_null() {
-        None
-    } else {
-        let string = unsafe { CStr::from_ptr(result) };
-        Some(string.to_str().ok()?.to_string())
+    #[test]
+    fn find_history_match_skips_to_older_entry_when_before_is_set() {
+        let history = vec!["cd /tmp".to_owned(), "ls".to_owned(), "cd /home".to_owned()];
+        assert_eq!(find_history_match(&history, "cd", Some(2)), Some(0));
     }
-}
 
-/// Wrapper around libc::open
-fn open_file(path: &str, flags: i32) -> Result<i32, io::Error> {
-    let result = unsafe { libc::open(path.as_ptr() as *const i8, flags) };
-    if result == -1 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(result)
+    #[test]
+    fn find_history_match_empty_query_matches_nothing() {
+        let history = vec!["ls".to_owned()];
+        assert_eq!(find_history_match(&history, "", None), None);
     }
-}
 
-/// Wrapper around libc::close
-fn close_file(fd: i32) -> Result<(), io::Error> {
-    let result = unsafe { libc::close(fd) };
-    if result == 0 {
-        Ok(())
-    } else {
-        Err(io::Error::last_os_error())
+    #[test]
+    fn longest_common_prefix_of_empty_list_is_empty() {
+        assert_eq!(longest_common_prefix(&[]), "");
     }
-}
 
-fn grantpt(pty: i32) -> Result<(), io::Error> {
-    if unsafe { libc::grantpt(pty) } != 0 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(())
+    #[test]
+    fn longest_common_prefix_stops_at_first_divergence() {
+        let candidates = vec!["status".to_owned(), "stop".to_owned(), "start".to_owned()];
+        assert_eq!(longest_common_prefix(&candidates), "st");
     }
-}
 
-fn unlockpt(pty: i32) -> Result<(), io::Error> {
-    if unsafe { libc::unlockpt(pty) } != 0 {
-        Err(io::Error::last_os_error())
-    } else {
-        Ok(())
+    #[test]
+    fn longest_common_prefix_of_single_candidate_is_itself() {
+        assert_eq!(longest_common_prefix(&["status".to_owned()]), "status");
+    }
+
+    #[test]
+    fn tab_action_with_no_candidates_does_nothing() {
+        assert_eq!(tab_action("fo", &[], None), TabAction::None);
+    }
+
+    #[test]
+    fn tab_action_with_one_candidate_completes_immediately() {
+        let candidates = vec!["foobar".to_owned()];
+        assert_eq!(
+            tab_action("foo", &candidates, None),
+            TabAction::Complete("foobar".to_owned())
+        );
+    }
+
+    #[test]
+    fn tab_action_widens_to_the_common_prefix_when_one_exists() {
+        let candidates = vec!["status".to_owned(), "stop".to_owned()];
+        assert_eq!(
+            tab_action("s", &candidates, None),
+            TabAction::Complete("st".to_owned())
+        );
+    }
+
+    #[test]
+    fn tab_action_lists_on_the_tab_right_after_widening() {
+        // After widening "s" to "st", the word under the cursor is now "st"
+        // with no further common prefix to add; the immediately-following
+        // Tab (last_tab_word == Some("st")) should list, not wait for a third.
+        let candidates = vec!["status".to_owned(), "stop".to_owned()];
+        assert_eq!(
+            tab_action("st", &candidates, Some("st")),
+            TabAction::List
+        );
+    }
+
+    #[test]
+    fn tab_action_remembers_word_on_first_tab_with_no_wider_prefix() {
+        let candidates = vec!["status".to_owned(), "stop".to_owned()];
+        assert_eq!(
+            tab_action("st", &candidates, None),
+            TabAction::Remember("st".to_owned())
+        );
+    }
+
+    #[test]
+    fn char_width_is_zero_for_combining_marks() {
+        assert_eq!(char_width('\u{0301}'), 0);
+    }
+
+    #[test]
+    fn char_width_is_two_for_wide_cjk_chars() {
+        assert_eq!(char_width('漢'), 2);
+    }
+
+    #[test]
+    fn char_width_is_one_for_ascii() {
+        assert_eq!(char_width('a'), 1);
+    }
+
+    #[test]
+    fn display_width_sums_char_widths() {
+        assert_eq!(display_width("a漢"), 3);
+    }
+
+    #[test]
+    fn incomplete_utf8_suffix_len_is_zero_for_complete_ascii() {
+        assert_eq!(incomplete_utf8_suffix_len(b"hello"), 0);
+    }
+
+    #[test]
+    fn incomplete_utf8_suffix_len_is_zero_for_complete_multibyte_char() {
+        assert_eq!(incomplete_utf8_suffix_len("漢".as_bytes()), 0);
+    }
+
+    #[test]
+    fn incomplete_utf8_suffix_len_detects_a_split_multibyte_char() {
+        let bytes = "漢".as_bytes();
+        // Only the lead byte arrived so far; the two continuation bytes are missing.
+        assert_eq!(incomplete_utf8_suffix_len(&bytes[..1]), 1);
     }
 }
@@ -20,20 +20,19 @@ struct Args {
 fn main() {
     let args: Args = Args::parse();
 
-    let rlwrap = Rlwrap::setup(RlwrapConfig {
+    let mut rlwrap = Rlwrap::setup(RlwrapConfig {
         stop_on_ctrl_c: true,
         prefix: args.substitute_prompt,
+        ..Default::default()
     })
     .unwrap();
 
-    match Command::new(args.program).args(&args.args).spawn() {
-        Ok(mut child) => {
-            child.wait().unwrap();
-        }
-        Err(e) => {
-            println!("Failed to spawn process: {e:?}");
-        }
+    let mut command = Command::new(args.program);
+    command.args(&args.args);
+    if let Err(e) = rlwrap.spawn(command) {
+        println!("Failed to spawn process: {e:?}");
+        return;
     }
 
-    Rlwrap::stop_gracefully(&rlwrap).unwrap();
+    rlwrap.run().unwrap();
 }
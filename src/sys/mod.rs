@@ -0,0 +1,208 @@
+//! Platform-specific pseudo-terminal plumbing, kept behind the [`PtyBackend`] trait
+//! so `lib.rs` never has to branch on `cfg(unix)`/`cfg(windows)` itself. This mirrors
+//! how std historically split its own platform glue into `sys::unix`/`sys::windows`
+//! modules behind a common interface.
+
+use std::io;
+use std::process::Command;
+
+#[cfg(target_family = "unix")]
+pub mod unix;
+#[cfg(target_family = "windows")]
+pub mod windows;
+
+#[cfg(target_family = "unix")]
+pub use unix::{MasterFd, RawDescriptor, SlaveHandle, SpawnedChild, UnixBackend as Backend};
+#[cfg(target_family = "windows")]
+pub use windows::{MasterFd, RawDescriptor, SlaveHandle, SpawnedChild, WindowsBackend as Backend};
+
+/// Which of the two watched descriptors (if any) has data ready to read.
+pub struct ReadyFds {
+    pub stdin: bool,
+    pub master: bool,
+}
+
+/// Duration `wait_ready` blocks for before returning with nothing ready, giving
+/// the event loop a chance to notice an external stop request.
+pub const WAIT_TIMEOUT_MS: i32 = 100;
+
+/// Blocks until `stdin` or `master` has data ready to read, or `WAIT_TIMEOUT_MS`
+/// elapses.
+pub fn wait_ready(stdin: RawDescriptor, master: &MasterFd) -> io::Result<ReadyFds> {
+    #[cfg(target_family = "unix")]
+    return unix::wait_ready(stdin, master, WAIT_TIMEOUT_MS);
+    #[cfg(target_family = "windows")]
+    return windows::wait_ready(stdin, master, WAIT_TIMEOUT_MS);
+}
+
+/// Puts a descriptor in non-blocking mode (a no-op on Windows; see that module).
+#[cfg(target_family = "unix")]
+pub fn set_nonblocking(descriptor: RawDescriptor) -> io::Result<()> {
+    unix::set_nonblocking(descriptor)
+}
+#[cfg(target_family = "windows")]
+pub fn set_nonblocking(descriptor: RawDescriptor) -> io::Result<()> {
+    windows::set_nonblocking(descriptor)
+}
+
+/// Puts the master end(s) in non-blocking mode (a no-op on Windows; see that module).
+#[cfg(target_family = "unix")]
+pub fn set_master_nonblocking(master: &MasterFd) -> io::Result<()> {
+    unix::set_nonblocking(*master)
+}
+#[cfg(target_family = "windows")]
+pub fn set_master_nonblocking(master: &MasterFd) -> io::Result<()> {
+    windows::set_nonblocking(master.input)?;
+    windows::set_nonblocking(master.output)
+}
+
+/// Creates the platform's default backend.
+pub fn backend() -> Backend {
+    Backend::default()
+}
+
+/// Abstracts over the OS-specific APIs used to create a pseudo-terminal and
+/// attach a child process to it.
+///
+/// Unix builds implement this over `posix_openpt`/`grantpt`/`unlockpt`.
+/// Windows builds implement it over the ConPTY API (`CreatePseudoConsole`)
+/// backed by anonymous pipes; the pseudo-console is created at a fixed 80x24
+/// and is not resized afterwards.
+pub trait PtyBackend {
+    /// Puts the current terminal into raw/character mode, so keystrokes reach
+    /// the prompt one at a time instead of being line-buffered by the OS.
+    fn enable_raw_mode(&self) -> io::Result<()>;
+    /// Restores whatever terminal mode was active before `enable_raw_mode`.
+    fn disable_raw_mode(&self) -> io::Result<()>;
+
+    /// Opens a new pseudo-terminal, returning the master end (read/written by
+    /// the prompt) and a handle identifying the slave end the wrapped program
+    /// should be attached to.
+    fn open(&self) -> io::Result<(MasterFd, SlaveHandle)>;
+
+    /// Closes the slave handle, once every attached child (or, on Windows, the
+    /// pseudo-console itself) no longer needs it.
+    fn close_slave(&self, handle: SlaveHandle) -> io::Result<()>;
+
+    /// Closes the master end(s) returned by `open`, called when the prompt stops.
+    fn close_master(&self, master: &MasterFd) -> io::Result<()>;
+}
+
+/// Spawns `cmd` attached to the pty identified by `master`/`slave`, rather than
+/// via the process-wide stdin/stdout/stderr redirection `setup` used to rely on.
+///
+/// Unix attaches the child directly through `Stdio`, duplicating `slave` for
+/// each stream. The pseudo-console on Windows isn't itself a readable/writable
+/// handle, so that build instead calls `CreateProcessW` with the
+/// `PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE` attribute, which is why this returns
+/// a platform-specific [`SpawnedChild`] rather than `std::process::Child`.
+#[cfg(target_family = "unix")]
+pub fn spawn_attached(
+    _master: &MasterFd,
+    slave: SlaveHandle,
+    cmd: Command,
+) -> io::Result<SpawnedChild> {
+    unix::spawn_attached(slave, cmd)
+}
+#[cfg(target_family = "windows")]
+pub fn spawn_attached(
+    master: &MasterFd,
+    slave: SlaveHandle,
+    cmd: Command,
+) -> io::Result<SpawnedChild> {
+    windows::spawn_attached(master, slave, cmd)
+}
+
+/// Polls `child` for exit without blocking, returning `Ok(None)` while it's
+/// still running.
+#[cfg(target_family = "unix")]
+pub fn try_wait(child: &mut SpawnedChild) -> io::Result<Option<i32>> {
+    Ok(child.try_wait()?.map(|status| status.code().unwrap_or(-1)))
+}
+#[cfg(target_family = "windows")]
+pub fn try_wait(child: &mut SpawnedChild) -> io::Result<Option<i32>> {
+    windows::try_wait(child)
+}
+
+/// Forcibly terminates `child`, e.g. after a `wait_child` timeout.
+#[cfg(target_family = "unix")]
+pub fn kill_child(child: &mut SpawnedChild) -> io::Result<()> {
+    child.kill()
+}
+#[cfg(target_family = "windows")]
+pub fn kill_child(child: &mut SpawnedChild) -> io::Result<()> {
+    windows::kill_child(child)
+}
+
+/// The descriptor identifying the real stdin this process was launched with,
+/// used to read keystrokes and to `poll`/`wait_ready` on.
+#[cfg(target_family = "unix")]
+pub fn stdin_descriptor() -> RawDescriptor {
+    unix::STDIN
+}
+#[cfg(target_family = "windows")]
+pub fn stdin_descriptor() -> RawDescriptor {
+    windows::stdin_handle()
+}
+
+/// The descriptor identifying the real stdout this process was launched with,
+/// used to draw the prompt.
+#[cfg(target_family = "unix")]
+pub fn stdout_descriptor() -> RawDescriptor {
+    unix::STDOUT
+}
+#[cfg(target_family = "windows")]
+pub fn stdout_descriptor() -> RawDescriptor {
+    windows::stdout_handle()
+}
+
+/// Wraps the master end(s) for reading the wrapped program's output.
+#[cfg(target_family = "unix")]
+pub fn master_reader(master: &MasterFd) -> Box<dyn io::Read + Send> {
+    Box::new(unix::RawIo(*master))
+}
+#[cfg(target_family = "windows")]
+pub fn master_reader(master: &MasterFd) -> Box<dyn io::Read + Send> {
+    Box::new(windows::RawIo(master.output))
+}
+
+/// Wraps the master end(s) for writing submitted input to the wrapped program.
+#[cfg(target_family = "unix")]
+pub fn master_writer(master: &MasterFd) -> Box<dyn io::Write + Send> {
+    Box::new(unix::RawIo(*master))
+}
+#[cfg(target_family = "windows")]
+pub fn master_writer(master: &MasterFd) -> Box<dyn io::Write + Send> {
+    Box::new(windows::RawIo(master.input))
+}
+
+/// Wraps a raw stdin/stdout/stderr descriptor (as returned by [`stdin_descriptor`]/[`stdout_descriptor`]) for reading.
+#[cfg(target_family = "unix")]
+pub fn descriptor_reader(descriptor: unix::RawDescriptor) -> Box<dyn io::Read + Send> {
+    Box::new(unix::RawIo(descriptor))
+}
+#[cfg(target_family = "windows")]
+pub fn descriptor_reader(descriptor: windows::RawDescriptor) -> Box<dyn io::Read + Send> {
+    Box::new(windows::RawIo(descriptor))
+}
+
+/// Wraps a raw stdin/stdout/stderr descriptor (as returned by [`stdin_descriptor`]/[`stdout_descriptor`]) for writing.
+#[cfg(target_family = "unix")]
+pub fn descriptor_writer(descriptor: unix::RawDescriptor) -> Box<dyn io::Write + Send> {
+    Box::new(unix::RawIo(descriptor))
+}
+#[cfg(target_family = "windows")]
+pub fn descriptor_writer(descriptor: windows::RawDescriptor) -> Box<dyn io::Write + Send> {
+    Box::new(windows::RawIo(descriptor))
+}
+
+/// Sends an interrupt to the current process, mirroring what the terminal driver
+/// would do on Ctrl-C (`SIGINT` on Unix, a synthesized Ctrl-C console event on Windows).
+#[cfg(target_family = "unix")]
+pub fn send_interrupt() -> io::Result<()> {
+    unix::kill(std::process::id() as i32, libc::SIGINT)
+}
+#[cfg(target_family = "windows")]
+pub fn send_interrupt() -> io::Result<()> {
+    windows::generate_ctrl_c()
+}
@@ -0,0 +1,242 @@
+//! Unix pty backend, built on `posix_openpt`/`grantpt`/`unlockpt` and `dup`.
+
+use std::{
+    ffi::CStr,
+    io,
+    io::Stdout,
+    os::unix::io::FromRawFd,
+    process::{Child, Command, Stdio},
+    sync::Mutex,
+};
+
+use termion::raw::{IntoRawMode, RawTerminal};
+
+use super::PtyBackend;
+
+/// The real stdin/stdout file descriptors, used directly since `setup` no
+/// longer dup2's the pty slave over the process's own std handles.
+pub const STDIN: i32 = libc::STDIN_FILENO;
+pub const STDOUT: i32 = libc::STDOUT_FILENO;
+
+/// A spawned child process. On Unix this is just `std::process::Child`.
+pub type SpawnedChild = Child;
+
+/// Raw file descriptor for the pty master end.
+pub type MasterFd = i32;
+/// Raw file descriptor identifying the pty slave end.
+pub type SlaveHandle = i32;
+/// Raw file descriptor, as returned by [`super::stdin_descriptor`]/[`super::stdout_descriptor`].
+pub type RawDescriptor = i32;
+
+/// A non-owning `Read`/`Write` wrapper around a raw file descriptor: unlike `File`,
+/// dropping it does not close the descriptor, since its lifetime is managed
+/// elsewhere (the pty master is closed explicitly by `Rlwrap::stop`; stdin and
+/// stdout are left open).
+pub struct RawIo(pub i32);
+
+impl io::Read for RawIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = unsafe { libc::read(self.0, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+}
+
+impl io::Write for RawIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n =
+            unsafe { libc::write(self.0, buf.as_ptr() as *const libc::c_void, buf.len()) };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Previous terminal state.
+/// This is static so the application can try revert it when a panic ocurs.
+pub static RAW_TERMINAL_STATE: Mutex<Option<RawTerminal<Stdout>>> = Mutex::new(None);
+
+#[derive(Default)]
+pub struct UnixBackend;
+
+impl PtyBackend for UnixBackend {
+    fn enable_raw_mode(&self) -> io::Result<()> {
+        let raw_term = io::stdout().into_raw_mode()?;
+        match RAW_TERMINAL_STATE.lock() {
+            Ok(mut guard) => {
+                *guard = Some(raw_term);
+                Ok(())
+            }
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Failed to aquire RAW_TERMINAL_STATE lock",
+            )),
+        }
+    }
+
+    fn disable_raw_mode(&self) -> io::Result<()> {
+        if let Ok(mut guard) = RAW_TERMINAL_STATE.lock() {
+            guard.take();
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<(MasterFd, SlaveHandle)> {
+        let master = open_pty(libc::O_RDWR | libc::O_CLOEXEC)?;
+        grantpt(master)?;
+        unlockpt(master)?;
+        let name = pty_name(master)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "Failed to get pty name"))?;
+        let slave = open_file(&name, libc::O_RDWR | libc::O_CLOEXEC)?;
+        Ok((master, slave))
+    }
+
+    fn close_slave(&self, handle: SlaveHandle) -> io::Result<()> {
+        close_file(handle)
+    }
+
+    fn close_master(&self, master: &MasterFd) -> io::Result<()> {
+        close_file(*master)
+    }
+}
+
+/// Wrapper around libc::dup
+pub fn dup(fd: i32) -> Result<i32, io::Error> {
+    let result = unsafe { libc::dup(fd) };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Duplicates `slave` into a `Stdio`: `Command` takes ownership of (and closes)
+/// whichever `Stdio` it's given, so stdin/stdout/stderr each need their own fd.
+fn slave_stdio(slave: SlaveHandle) -> io::Result<Stdio> {
+    let fd = dup(slave)?;
+    Ok(unsafe { Stdio::from_raw_fd(fd) })
+}
+
+/// Spawns `cmd` attached to the pty `slave`, closing `slave` itself once the
+/// child has its own duplicated descriptors.
+pub fn spawn_attached(slave: SlaveHandle, mut cmd: Command) -> io::Result<SpawnedChild> {
+    cmd.stdin(slave_stdio(slave)?);
+    cmd.stdout(slave_stdio(slave)?);
+    cmd.stderr(slave_stdio(slave)?);
+    let child = cmd.spawn()?;
+    close_file(slave)?;
+    Ok(child)
+}
+
+/// Puts `fd` in non-blocking mode, mirroring the `fcntl(O_NONBLOCK)` dance used
+/// elsewhere for pipes: reads on a ready-but-empty fd return `WouldBlock` instead
+/// of hanging the event loop.
+pub fn set_nonblocking(fd: i32) -> io::Result<()> {
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Blocks until `stdin` or `master` has data ready to read, or `timeout_ms` elapses.
+pub fn wait_ready(stdin: i32, master: &MasterFd, timeout_ms: i32) -> io::Result<super::ReadyFds> {
+    let mut fds = [
+        libc::pollfd {
+            fd: stdin,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: *master,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout_ms) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(super::ReadyFds {
+        stdin: fds[0].revents & libc::POLLIN != 0,
+        master: fds[1].revents & libc::POLLIN != 0,
+    })
+}
+
+/// Wrapper around libc::kill
+pub fn kill(pid: i32, sig: i32) -> Result<(), io::Error> {
+    if unsafe { libc::kill(pid, sig) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Wrapper around libc::posix_openpt
+fn open_pty(flags: i32) -> Result<i32, io::Error> {
+    let result = unsafe { libc::posix_openpt(flags) };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Wrapper around libc::ptsname
+fn pty_name(fd: i32) -> Option<String> {
+    let result = unsafe { libc::ptsname(fd) };
+    if result.is_null() {
+        None
+    } else {
+        let string = unsafe { CStr::from_ptr(result) };
+        Some(string.to_str().ok()?.to_string())
+    }
+}
+
+/// Wrapper around libc::open
+fn open_file(path: &str, flags: i32) -> Result<i32, io::Error> {
+    let result = unsafe { libc::open(path.as_ptr() as *const i8, flags) };
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(result)
+    }
+}
+
+/// Wrapper around libc::close
+pub fn close_file(fd: i32) -> Result<(), io::Error> {
+    let result = unsafe { libc::close(fd) };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn grantpt(pty: i32) -> Result<(), io::Error> {
+    if unsafe { libc::grantpt(pty) } != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+fn unlockpt(pty: i32) -> Result<(), io::Error> {
+    if unsafe { libc::unlockpt(pty) } != 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
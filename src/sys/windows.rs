@@ -0,0 +1,476 @@
+//! Windows pty backend, built on the ConPTY API (`CreatePseudoConsole`, at a
+//! fixed 80x24 that is not resized afterwards) backed by anonymous pipes for
+//! the master read/write ends,
+//! with `SetConsoleMode` used for raw input instead of termion's `IntoRawMode`
+//! (termion does not support Windows consoles).
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE, WAIT_OBJECT_0};
+use windows_sys::Win32::Storage::FileSystem::{ReadFile, WriteFile};
+use windows_sys::Win32::System::Console::{
+    ClosePseudoConsole, CreatePipe, CreatePseudoConsole, GenerateConsoleCtrlEvent,
+    GetConsoleMode, GetNumberOfConsoleInputEvents, GetStdHandle, SetConsoleMode,
+    CTRL_C_EVENT, ENABLE_ECHO_INPUT, ENABLE_LINE_INPUT, ENABLE_PROCESSED_INPUT,
+    ENABLE_VIRTUAL_TERMINAL_INPUT, STD_INPUT_HANDLE, STD_OUTPUT_HANDLE,
+};
+use windows_sys::Win32::System::Pipes::PeekNamedPipe;
+use windows_sys::Win32::System::Threading::{
+    CreateProcessW, DeleteProcThreadAttributeList, GetExitCodeProcess,
+    InitializeProcThreadAttributeList, TerminateProcess, UpdateProcThreadAttribute,
+    WaitForSingleObject, CREATE_UNICODE_ENVIRONMENT, EXTENDED_STARTUPINFO_PRESENT,
+    LPPROC_THREAD_ATTRIBUTE_LIST, PROCESS_INFORMATION, PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE,
+    STARTUPINFOEXW,
+};
+
+use super::PtyBackend;
+
+/// The prompt-facing ends of the pipes backing the pseudo-console: `input` is
+/// written to feed the child's console input, `output` is read to get the
+/// child's console output.
+#[derive(Clone, Copy)]
+pub struct MasterFd {
+    pub input: HANDLE,
+    pub output: HANDLE,
+}
+
+/// Handle to the ConPTY pseudo-console the child process is attached to.
+pub type SlaveHandle = HANDLE;
+/// Raw handle, as returned by [`super::stdin_descriptor`]/[`super::stdout_descriptor`].
+pub type RawDescriptor = HANDLE;
+
+/// A non-owning `Read`/`Write` wrapper around a raw Win32 handle: dropping it does
+/// not close the handle, since its lifetime is managed elsewhere (the master
+/// handles are closed explicitly by `Rlwrap::stop`; the std handles are left open).
+pub struct RawIo(pub HANDLE);
+
+impl io::Read for RawIo {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read = 0u32;
+        let ok = unsafe {
+            ReadFile(
+                self.0,
+                buf.as_mut_ptr(),
+                buf.len() as u32,
+                &mut read,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(read as usize)
+        }
+    }
+}
+
+impl io::Write for RawIo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut written = 0u32;
+        let ok = unsafe {
+            WriteFile(
+                self.0,
+                buf.as_ptr(),
+                buf.len() as u32,
+                &mut written,
+                std::ptr::null_mut(),
+            )
+        };
+        if ok == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(written as usize)
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Synthesizes a Ctrl-C console event for the current process group, mirroring
+/// `SIGINT` on Unix.
+pub fn generate_ctrl_c() -> io::Result<()> {
+    if unsafe { GenerateConsoleCtrlEvent(CTRL_C_EVENT, 0) } == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// No-op: there is no Windows equivalent of `fcntl(O_NONBLOCK)` for console
+/// handles or anonymous pipes, so readiness is established up front by
+/// `wait_ready` instead.
+pub fn set_nonblocking(_handle: HANDLE) -> io::Result<()> {
+    Ok(())
+}
+
+/// Waits until `stdin` or `master` has data ready to read, or `timeout_ms` elapses.
+///
+/// Anonymous pipes and console input handles aren't waitable objects, so unlike
+/// the Unix backend's `libc::poll` this polls `GetNumberOfConsoleInputEvents`/
+/// `PeekNamedPipe` in a short sleep loop rather than blocking on the OS.
+pub fn wait_ready(
+    stdin: HANDLE,
+    master: &MasterFd,
+    timeout_ms: i32,
+) -> io::Result<super::ReadyFds> {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms.max(0) as u64);
+    loop {
+        let mut stdin_events = 0u32;
+        if unsafe { GetNumberOfConsoleInputEvents(stdin, &mut stdin_events) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut master_available = 0u32;
+        unsafe {
+            PeekNamedPipe(
+                master.output,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                &mut master_available,
+                std::ptr::null_mut(),
+            )
+        };
+        if stdin_events > 0 || master_available > 0 {
+            return Ok(super::ReadyFds {
+                stdin: stdin_events > 0,
+                master: master_available > 0,
+            });
+        }
+        if Instant::now() >= deadline {
+            return Ok(super::ReadyFds {
+                stdin: false,
+                master: false,
+            });
+        }
+        std::thread::sleep(Duration::from_millis(15));
+    }
+}
+
+/// The real stdin/stdout handles, used directly since `setup` no longer swaps
+/// the process's own std handles.
+pub fn stdin_handle() -> HANDLE {
+    unsafe { GetStdHandle(STD_INPUT_HANDLE) }
+}
+pub fn stdout_handle() -> HANDLE {
+    unsafe { GetStdHandle(STD_OUTPUT_HANDLE) }
+}
+
+/// A ConPTY-attached child process. `std::process::Child` can't represent this
+/// directly: attaching to a pseudo console requires `CreateProcessW` with an
+/// extended attribute list rather than `Stdio` handles (the pseudo console
+/// itself isn't a readable/writable handle), so this wraps the raw process
+/// handle instead.
+pub struct SpawnedChild {
+    process: HANDLE,
+}
+
+/// Spawns `cmd` attached to the pseudo console behind `slave`, using
+/// `CreateProcessW` with the `PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE` attribute —
+/// the documented way to attach a child to ConPTY. Consumes `slave`, closing
+/// the pseudo-console once the child is attached, mirroring how the Unix
+/// backend closes its `slave` once the child has its own duplicated fds.
+pub fn spawn_attached(
+    _master: &MasterFd,
+    slave: SlaveHandle,
+    cmd: Command,
+) -> io::Result<SpawnedChild> {
+    let mut command_line = build_command_line(&cmd);
+    let environment_block = build_environment_block(&cmd);
+    let current_dir: Option<Vec<u16>> = cmd
+        .get_current_dir()
+        .map(|dir| OsStr::new(dir).encode_wide().chain(Some(0)).collect());
+
+    unsafe {
+        let mut attr_list_size: usize = 0;
+        InitializeProcThreadAttributeList(std::ptr::null_mut(), 1, 0, &mut attr_list_size);
+        let mut attr_list_buf = vec![0u8; attr_list_size];
+        let attr_list = attr_list_buf.as_mut_ptr() as LPPROC_THREAD_ATTRIBUTE_LIST;
+        if InitializeProcThreadAttributeList(attr_list, 1, 0, &mut attr_list_size) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut slave = slave;
+        let updated = UpdateProcThreadAttribute(
+            attr_list,
+            0,
+            PROC_THREAD_ATTRIBUTE_PSEUDOCONSOLE as usize,
+            &mut slave as *mut _ as *mut _,
+            std::mem::size_of::<HANDLE>(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        );
+        if updated == 0 {
+            DeleteProcThreadAttributeList(attr_list);
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut startup_info: STARTUPINFOEXW = std::mem::zeroed();
+        startup_info.StartupInfo.cb = std::mem::size_of::<STARTUPINFOEXW>() as u32;
+        startup_info.lpAttributeList = attr_list;
+
+        let mut process_info: PROCESS_INFORMATION = std::mem::zeroed();
+        let creation_flags = if environment_block.is_some() {
+            EXTENDED_STARTUPINFO_PRESENT | CREATE_UNICODE_ENVIRONMENT
+        } else {
+            EXTENDED_STARTUPINFO_PRESENT
+        };
+        let env_ptr = environment_block
+            .as_ref()
+            .map_or(std::ptr::null(), |block| block.as_ptr() as *const _);
+        let dir_ptr = current_dir
+            .as_ref()
+            .map_or(std::ptr::null(), |dir| dir.as_ptr());
+        let ok = CreateProcessW(
+            std::ptr::null(),
+            command_line.as_mut_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            0,
+            creation_flags,
+            env_ptr,
+            dir_ptr,
+            &startup_info.StartupInfo,
+            &mut process_info,
+        );
+        DeleteProcThreadAttributeList(attr_list);
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        CloseHandle(process_info.hThread);
+        ClosePseudoConsole(slave);
+        Ok(SpawnedChild {
+            process: process_info.hProcess,
+        })
+    }
+}
+
+/// Builds a quoted, NUL-terminated Windows command line (as UTF-16) from
+/// `cmd`'s program and arguments, since `CreateProcessW` (unlike
+/// `std::process::Command`) takes one already assembled and escaped instead
+/// of a separate argv.
+fn build_command_line(cmd: &Command) -> Vec<u16> {
+    let mut line: Vec<u16> = Vec::new();
+    append_arg(&mut line, cmd.get_program());
+    for arg in cmd.get_args() {
+        line.push(' ' as u16);
+        append_arg(&mut line, arg);
+    }
+    line.push(0);
+    line
+}
+
+/// Quotes and appends a single argument to `line`, following the same
+/// backslash/quote escaping rules the Microsoft C runtime's command line
+/// parser (`CommandLineToArgvW`) expects: a run of backslashes is only
+/// escaped (doubled) when it immediately precedes a `"`, either one embedded
+/// in the argument or the closing quote added here, and every literal `"` is
+/// itself escaped.
+fn append_arg(line: &mut Vec<u16>, arg: &OsStr) {
+    let needs_quotes = arg.is_empty()
+        || arg
+            .encode_wide()
+            .any(|c| c == ' ' as u16 || c == '\t' as u16 || c == '"' as u16);
+    if !needs_quotes {
+        line.extend(arg.encode_wide());
+        return;
+    }
+    line.push(b'"' as u16);
+    let mut backslashes: usize = 0;
+    for c in arg.encode_wide() {
+        if c == b'\\' as u16 {
+            backslashes += 1;
+        } else {
+            if c == b'"' as u16 {
+                line.extend((0..=backslashes).map(|_| b'\\' as u16));
+            }
+            backslashes = 0;
+        }
+        line.push(c);
+    }
+    line.extend((0..backslashes).map(|_| b'\\' as u16));
+    line.push(b'"' as u16);
+}
+
+/// Builds a `CreateProcessW`-style environment block (a sequence of
+/// NUL-terminated `"KEY=VALUE"` UTF-16 strings, itself terminated by an extra
+/// NUL) from `cmd`'s explicit `env`/`env_remove` overrides layered onto the
+/// current process's environment, mirroring what `std::process::Command`
+/// does internally on Unix. Returns `None` (meaning "inherit unmodified") if
+/// `cmd` has no overrides, so `spawn_attached` can skip `CREATE_UNICODE_ENVIRONMENT`
+/// and let the child inherit the parent's environment as-is.
+fn build_environment_block(cmd: &Command) -> Option<Vec<u16>> {
+    let overrides: Vec<_> = cmd.get_envs().collect();
+    if overrides.is_empty() {
+        return None;
+    }
+    let mut vars: std::collections::BTreeMap<_, _> = std::env::vars_os().collect();
+    for (key, value) in overrides {
+        match value {
+            Some(value) => {
+                vars.insert(key.to_os_string(), value.to_os_string());
+            }
+            None => {
+                vars.remove(key);
+            }
+        }
+    }
+    let mut block: Vec<u16> = Vec::new();
+    for (key, value) in vars {
+        block.extend(key.encode_wide());
+        block.push('=' as u16);
+        block.extend(value.encode_wide());
+        block.push(0);
+    }
+    block.push(0);
+    Some(block)
+}
+
+/// Polls `child` for exit without blocking.
+pub fn try_wait(child: &mut SpawnedChild) -> io::Result<Option<i32>> {
+    if unsafe { WaitForSingleObject(child.process, 0) } != WAIT_OBJECT_0 {
+        return Ok(None);
+    }
+    let mut code = 0u32;
+    if unsafe { GetExitCodeProcess(child.process, &mut code) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(Some(code as i32))
+}
+
+/// Forcibly terminates `child`.
+pub fn kill_child(child: &mut SpawnedChild) -> io::Result<()> {
+    if unsafe { TerminateProcess(child.process, 1) } == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+impl Drop for SpawnedChild {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.process);
+        }
+    }
+}
+
+/// Console mode flags saved before entering raw mode, restored by `disable_raw_mode`.
+static SAVED_CONSOLE_MODE: Mutex<Option<u32>> = Mutex::new(None);
+
+#[derive(Default)]
+pub struct WindowsBackend;
+
+impl PtyBackend for WindowsBackend {
+    fn enable_raw_mode(&self) -> io::Result<()> {
+        unsafe {
+            let stdin = GetStdHandle(STD_INPUT_HANDLE);
+            let mut mode = 0u32;
+            if GetConsoleMode(stdin, &mut mode) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+            if let Ok(mut guard) = SAVED_CONSOLE_MODE.lock() {
+                *guard = Some(mode);
+            }
+            let raw_mode = (mode & !(ENABLE_LINE_INPUT | ENABLE_ECHO_INPUT | ENABLE_PROCESSED_INPUT))
+                | ENABLE_VIRTUAL_TERMINAL_INPUT;
+            if SetConsoleMode(stdin, raw_mode) == 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn disable_raw_mode(&self) -> io::Result<()> {
+        let saved = SAVED_CONSOLE_MODE.lock().ok().and_then(|mut g| g.take());
+        if let Some(mode) = saved {
+            unsafe {
+                let stdin = GetStdHandle(STD_INPUT_HANDLE);
+                if SetConsoleMode(stdin, mode) == 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn open(&self) -> io::Result<(MasterFd, SlaveHandle)> {
+        unsafe {
+            // Pipe the pseudo-console reads its input from / the prompt writes to.
+            let (pty_input_read, pty_input_write) = create_pipe()?;
+            // Pipe the pseudo-console writes its output to / the prompt reads from.
+            let (pty_output_read, pty_output_write) = create_pipe()?;
+
+            let mut pty_handle: HANDLE = INVALID_HANDLE_VALUE;
+            let hr = CreatePseudoConsole(
+                windows_sys::Win32::System::Console::COORD { X: 80, Y: 24 },
+                pty_input_read,
+                pty_output_write,
+                0,
+                &mut pty_handle,
+            );
+            CloseHandle(pty_input_read);
+            CloseHandle(pty_output_write);
+            if hr != 0 {
+                CloseHandle(pty_input_write);
+                CloseHandle(pty_output_read);
+                return Err(hresult_to_io_error(hr));
+            }
+
+            let master = MasterFd {
+                input: pty_input_write,
+                output: pty_output_read,
+            };
+            Ok((master, pty_handle))
+        }
+    }
+
+    fn close_slave(&self, handle: SlaveHandle) -> io::Result<()> {
+        unsafe {
+            ClosePseudoConsole(handle);
+        }
+        Ok(())
+    }
+
+    fn close_master(&self, master: &MasterFd) -> io::Result<()> {
+        unsafe {
+            CloseHandle(master.input);
+            CloseHandle(master.output);
+        }
+        Ok(())
+    }
+}
+
+/// Converts an `HRESULT` (as returned by `CreatePseudoConsole`) into an
+/// `io::Error`. HRESULTs use a different encoding than the raw Win32 error
+/// codes `io::Error::from_raw_os_error` expects, so a Win32 code is only
+/// unpacked from it when the HRESULT actually wraps one (`FACILITY_WIN32`);
+/// otherwise the raw HRESULT is reported directly.
+fn hresult_to_io_error(hr: i32) -> io::Error {
+    const FACILITY_WIN32: i32 = 7;
+    if hr < 0 && (hr >> 16) & 0x1FFF == FACILITY_WIN32 {
+        io::Error::from_raw_os_error(hr & 0xFFFF)
+    } else {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("CreatePseudoConsole failed: HRESULT 0x{:08X}", hr as u32),
+        )
+    }
+}
+
+/// Creates an anonymous pipe, returning `(read_handle, write_handle)`.
+unsafe fn create_pipe() -> io::Result<(HANDLE, HANDLE)> {
+    let mut read_handle: HANDLE = INVALID_HANDLE_VALUE;
+    let mut write_handle: HANDLE = INVALID_HANDLE_VALUE;
+    if CreatePipe(&mut read_handle, &mut write_handle, std::ptr::null(), 0) == 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok((read_handle, write_handle))
+    }
+}